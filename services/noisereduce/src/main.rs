@@ -1,17 +1,93 @@
 use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
 
-use axum::{Router, body::Bytes, http::StatusCode, response::IntoResponse, routing::{get, post}};
+use axum::{
+    body::Bytes,
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        DefaultBodyLimit, State,
+    },
+    http::{header, StatusCode},
+    response::IntoResponse,
+    routing::{get, post},
+    Router,
+};
+use hyper::{body::Incoming, Request};
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use hyper_util::server::conn::auto::Builder;
 use nnnoiseless::DenoiseState;
+use tokio::net::TcpListener;
+use tokio::sync::Semaphore;
+use tokio_rustls::rustls::ServerConfig;
+use tokio_rustls::TlsAcceptor;
+use tower::Service;
 
 const FRAME_SIZE: usize = DenoiseState::FRAME_SIZE; // 480 samples (48kHz)
+const FRAME_16K: usize = FRAME_SIZE / 3; // 160 samples (16kHz)
 
-fn denoise_48k(samples: &[f32]) -> Vec<f32> {
+const DEFAULT_MAX_CONCURRENCY: usize = 4;
+const DEFAULT_TIMEOUT_MS: u64 = 30_000;
+const DEFAULT_MAX_BODY_BYTES: usize = 64 * 1024 * 1024; // 64 MiB
+
+/// Shared server limits, cloned into every handler.
+///
+/// The semaphore bounds how many denoise jobs run on the blocking pool at once
+/// so a flood of large bodies can't pin every thread; the timeout caps how long
+/// any single job may hold its permit.
+#[derive(Clone)]
+struct AppState {
+    sem: Arc<Semaphore>,
+    timeout: Duration,
+}
+
+impl AppState {
+    fn from_env() -> (Self, usize) {
+        let concurrency = env_usize("NOISEREDUCE_MAX_CONCURRENCY", DEFAULT_MAX_CONCURRENCY);
+        let timeout_ms = env_u64("NOISEREDUCE_TIMEOUT_MS", DEFAULT_TIMEOUT_MS);
+        let max_body = env_usize("NOISEREDUCE_MAX_BODY_BYTES", DEFAULT_MAX_BODY_BYTES);
+        let state = Self {
+            sem: Arc::new(Semaphore::new(concurrency.max(1))),
+            timeout: Duration::from_millis(timeout_ms),
+        };
+        (state, max_body)
+    }
+}
+
+fn env_usize(key: &str, default: usize) -> usize {
+    std::env::var(key).ok().and_then(|v| v.parse().ok()).unwrap_or(default)
+}
+
+fn env_u64(key: &str, default: u64) -> u64 {
+    std::env::var(key).ok().and_then(|v| v.parse().ok()).unwrap_or(default)
+}
+
+fn decode_f32_le(bytes: &[u8]) -> Vec<f32> {
+    bytes
+        .chunks_exact(4)
+        .map(|c| f32::from_le_bytes([c[0], c[1], c[2], c[3]]))
+        .collect()
+}
+
+fn encode_f32_le(samples: &[f32]) -> Vec<u8> {
+    samples.iter().flat_map(|s| s.to_le_bytes()).collect()
+}
+
+/// Denoise 48kHz samples, also collecting RNNoise's per-frame voice-activity
+/// probability (one score per 480-sample frame). When `gate` is set, frames
+/// whose probability falls below the threshold are zeroed in the output.
+fn denoise_48k_vad(samples: &[f32], gate: Option<f32>, cancel: &AtomicBool) -> (Vec<f32>, Vec<f32>) {
     let mut state = DenoiseState::new();
     let mut out = Vec::with_capacity(samples.len());
+    let mut vad = Vec::with_capacity(samples.len() / FRAME_SIZE + 1);
     let mut frame_out = [0.0f32; FRAME_SIZE];
 
     let chunks = samples.chunks(FRAME_SIZE);
     for chunk in chunks {
+        if cancel.load(Ordering::Relaxed) {
+            break;
+        }
         let input = if chunk.len() < FRAME_SIZE {
             let mut padded = [0.0f32; FRAME_SIZE];
             padded[..chunk.len()].copy_from_slice(chunk);
@@ -21,11 +97,112 @@ fn denoise_48k(samples: &[f32]) -> Vec<f32> {
             arr.copy_from_slice(chunk);
             arr
         };
-        state.process_frame(&mut frame_out, &input);
+        let prob = state.process_frame(&mut frame_out, &input);
+        vad.push(prob);
+        if gate.is_some_and(|t| prob < t) {
+            frame_out.fill(0.0);
+        }
         let take = chunk.len().min(FRAME_SIZE);
         out.extend_from_slice(&frame_out[..take]);
     }
-    out
+    (out, vad)
+}
+
+const RESAMPLE_TAPS: usize = 96; // 32 taps per polyphase sub-filter
+
+/// Windowed-sinc (Hann) low-pass prototype, cutoff at 8kHz in the 48k domain
+/// (normalized 1/6), DC-normalized to unity gain.
+fn design_lowpass(num_taps: usize) -> Vec<f32> {
+    use std::f64::consts::PI;
+    let m = (num_taps - 1) as f64;
+    let fc = 1.0 / 6.0;
+    let mut h = Vec::with_capacity(num_taps);
+    for n in 0..num_taps {
+        let x = n as f64 - m / 2.0;
+        let sinc = if x.abs() < 1e-9 {
+            2.0 * fc
+        } else {
+            (2.0 * PI * fc * x).sin() / (PI * x)
+        };
+        let w = 0.5 - 0.5 * (2.0 * PI * n as f64 / m).cos();
+        h.push(sinc * w);
+    }
+    let sum: f64 = h.iter().sum();
+    h.iter().map(|v| (v / sum) as f32).collect()
+}
+
+/// Band-limited polyphase resampler for the integer 16k↔48k factor of 3.
+///
+/// A single windowed-sinc prototype low-pass is shared by both directions: the
+/// up path splits it into three phase sub-filters (pre-scaled by 3) so the
+/// inserted zeros are never multiplied, and the down path convolves then keeps
+/// only every third output. Filter history is carried across calls so block
+/// boundaries don't click, which lets the streaming endpoint reuse one instance
+/// for the whole connection.
+struct Resampler3x {
+    proto: Vec<f32>,
+    poly_up: [Vec<f32>; 3],
+    up_hist: Vec<f32>,
+    down_hist: Vec<f32>,
+    down_in: usize,
+}
+
+impl Resampler3x {
+    fn new() -> Self {
+        let proto = design_lowpass(RESAMPLE_TAPS);
+        let phase_len = RESAMPLE_TAPS / 3;
+        let poly_up = std::array::from_fn(|p| {
+            (0..phase_len).map(|j| 3.0 * proto[p + 3 * j]).collect()
+        });
+        Self {
+            up_hist: vec![0.0; phase_len - 1],
+            down_hist: vec![0.0; RESAMPLE_TAPS - 1],
+            down_in: 0,
+            proto,
+            poly_up,
+        }
+    }
+
+    /// 16k → 48k, producing exactly `input.len() * 3` samples.
+    fn upsample(&mut self, input: &[f32]) -> Vec<f32> {
+        let keep = self.poly_up[0].len() - 1;
+        let mut buf = std::mem::take(&mut self.up_hist);
+        buf.extend_from_slice(input);
+        let mut out = Vec::with_capacity(input.len() * 3);
+        for i in keep..buf.len() {
+            for sub in &self.poly_up {
+                let acc = sub.iter().enumerate().map(|(j, &c)| c * buf[i - j]).sum();
+                out.push(acc);
+            }
+        }
+        self.up_hist = buf.split_off(buf.len() - keep);
+        out
+    }
+
+    /// 48k → 16k, emitting only the kept (decimated) outputs.
+    fn downsample(&mut self, input: &[f32]) -> Vec<f32> {
+        let keep = self.proto.len() - 1;
+        let mut buf = std::mem::take(&mut self.down_hist);
+        buf.extend_from_slice(input);
+        let base = self.down_in;
+        let end = base + input.len();
+        let mut out = Vec::with_capacity(input.len() / 3 + 1);
+        let mut g = base.div_ceil(3) * 3;
+        while g < end {
+            let pos = keep + (g - base);
+            let acc = self
+                .proto
+                .iter()
+                .enumerate()
+                .map(|(j, &c)| c * buf[pos - j])
+                .sum();
+            out.push(acc);
+            g += 3;
+        }
+        self.down_in = end;
+        self.down_hist = buf.split_off(buf.len() - keep);
+        out
+    }
 }
 
 fn upsample_3x(samples: &[f32]) -> Vec<f32> {
@@ -47,43 +224,622 @@ fn downsample_3x(samples: &[f32]) -> Vec<f32> {
     samples.iter().step_by(3).copied().collect()
 }
 
-fn denoise_16k(samples: &[f32]) -> Vec<f32> {
-    let up = upsample_3x(samples);
-    let denoised = denoise_48k(&up);
-    downsample_3x(&denoised)
+/// 16kHz counterpart of [`denoise_48k_vad`]. The returned VAD scores are one
+/// per 160-sample input frame (the 48k frames map 1:1 after the 3x bridge).
+fn denoise_16k_vad(samples: &[f32], gate: Option<f32>, fast: bool, cancel: &AtomicBool) -> (Vec<f32>, Vec<f32>) {
+    let up = if fast {
+        upsample_3x(samples)
+    } else {
+        Resampler3x::new().upsample(samples)
+    };
+    let (denoised, vad) = denoise_48k_vad(&up, gate, cancel);
+    let down = if fast {
+        downsample_3x(&denoised)
+    } else {
+        Resampler3x::new().downsample(&denoised)
+    };
+    (down, vad)
 }
 
-async fn handle_denoise(body: Bytes) -> impl IntoResponse {
-    if body.len() % 4 != 0 {
-        return (StatusCode::BAD_REQUEST, "body must be float32 LE samples").into_response();
+/// Per-connection denoiser for the streaming endpoint.
+///
+/// Unlike the one-shot handlers, this keeps a single [`DenoiseState`] alive for
+/// the whole socket so RNNoise's adaptive noise-floor estimate survives across
+/// frames instead of being reset on every request. Incoming 16kHz samples are
+/// buffered until a full 160-sample frame is available; leftover samples are
+/// held in `residual` and emitted on [`StreamDenoiser::flush`] when the client
+/// disconnects.
+struct StreamDenoiser {
+    state: Box<DenoiseState<'static>>,
+    residual: Vec<f32>,
+    resampler: Option<Resampler3x>,
+}
+
+impl StreamDenoiser {
+    fn new(fast: bool) -> Self {
+        Self {
+            state: DenoiseState::new(),
+            residual: Vec::new(),
+            resampler: (!fast).then(Resampler3x::new),
+        }
     }
 
-    let samples: Vec<f32> = body
-        .chunks_exact(4)
-        .map(|c| f32::from_le_bytes([c[0], c[1], c[2], c[3]]))
-        .collect();
+    fn denoise_frame(&mut self, frame_16k: &[f32]) -> Vec<f32> {
+        let up = match &mut self.resampler {
+            Some(r) => r.upsample(frame_16k),
+            None => upsample_3x(frame_16k),
+        };
+        let mut input = [0.0f32; FRAME_SIZE];
+        input.copy_from_slice(&up);
+        let mut frame_out = [0.0f32; FRAME_SIZE];
+        self.state.process_frame(&mut frame_out, &input);
+        match &mut self.resampler {
+            Some(r) => r.downsample(&frame_out),
+            None => downsample_3x(&frame_out),
+        }
+    }
+
+    /// Push raw 16kHz samples and return whatever whole frames are now ready.
+    fn push(&mut self, samples: &[f32]) -> Vec<f32> {
+        self.residual.extend_from_slice(samples);
+        let mut out = Vec::new();
+        while self.residual.len() >= FRAME_16K {
+            let frame: Vec<f32> = self.residual.drain(..FRAME_16K).collect();
+            out.extend_from_slice(&self.denoise_frame(&frame));
+        }
+        out
+    }
+
+    /// Denoise and return any partial frame still buffered at end of stream.
+    fn flush(&mut self) -> Vec<f32> {
+        if self.residual.is_empty() {
+            return Vec::new();
+        }
+        let valid = self.residual.len();
+        let mut frame = std::mem::take(&mut self.residual);
+        frame.resize(FRAME_16K, 0.0);
+        let down = self.denoise_frame(&frame);
+        down[..valid.min(down.len())].to_vec()
+    }
+}
+
+/// Query parameters for [`handle_denoise`].
+#[derive(serde::Deserialize)]
+struct DenoiseParams {
+    /// When non-zero, append the per-frame VAD scores to the response.
+    vad: Option<u8>,
+    /// When set, zero any frame whose VAD probability is below this threshold.
+    gate: Option<f32>,
+    /// When non-zero, use the low-latency linear resampler instead of the FIR.
+    fast: Option<u8>,
+    /// Sample rate for raw (non-WAV) bodies; defaults to 16000.
+    rate: Option<u32>,
+}
+
+/// Sample encoding of a PCM payload: 32-bit float or 16-bit integer.
+#[derive(Clone, Copy)]
+enum SampleFormat {
+    F32,
+    Pcm16,
+}
+
+/// How a decoded request should be re-encoded so the reply matches the input.
+enum Container {
+    RawF32,
+    RawPcm16,
+    Wav(SampleFormat),
+}
+
+/// A decoded, mono f32 request plus the metadata needed to echo it back.
+struct AudioInput {
+    samples: Vec<f32>,
+    rate: u32,
+    container: Container,
+}
+
+/// Linear-interpolation resampler for the arbitrary rates WAV callers may send;
+/// the integer 16k/48k paths use the band-limited [`Resampler3x`] instead.
+fn resample_linear(samples: &[f32], from: u32, to: u32) -> Vec<f32> {
+    if from == to || samples.is_empty() {
+        return samples.to_vec();
+    }
+    let ratio = from as f64 / to as f64;
+    let out_len = ((samples.len() as f64) * to as f64 / from as f64).round() as usize;
+    (0..out_len)
+        .map(|i| {
+            let src = i as f64 * ratio;
+            let idx = src.floor() as usize;
+            let frac = (src - idx as f64) as f32;
+            let a = samples[idx.min(samples.len() - 1)];
+            let b = samples[(idx + 1).min(samples.len() - 1)];
+            a + (b - a) * frac
+        })
+        .collect()
+}
+
+/// Denoise mono samples at their native rate, bridging to RNNoise's 48k as
+/// needed and mapping the result back. Returns the denoised audio and the
+/// per-48k-frame VAD scores.
+fn denoise_at_rate(
+    samples: &[f32],
+    rate: u32,
+    gate: Option<f32>,
+    fast: bool,
+    cancel: &AtomicBool,
+) -> (Vec<f32>, Vec<f32>) {
+    match rate {
+        48_000 => denoise_48k_vad(samples, gate, cancel),
+        16_000 => denoise_16k_vad(samples, gate, fast, cancel),
+        other => {
+            let up = resample_linear(samples, other, 48_000);
+            let (denoised, vad) = denoise_48k_vad(&up, gate, cancel);
+            (resample_linear(&denoised, 48_000, other), vad)
+        }
+    }
+}
+
+/// Parse a RIFF/WAVE body into mono f32 samples, its rate, and sample format.
+fn parse_wav(bytes: &[u8]) -> Option<(Vec<f32>, u32, SampleFormat)> {
+    if bytes.len() < 12 || &bytes[0..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+        return None;
+    }
+    let le16 = |b: &[u8]| u16::from_le_bytes([b[0], b[1]]);
+    let le32 = |b: &[u8]| u32::from_le_bytes([b[0], b[1], b[2], b[3]]);
+
+    let mut fmt: Option<&[u8]> = None;
+    let mut data: Option<&[u8]> = None;
+    let mut pos = 12;
+    while pos + 8 <= bytes.len() {
+        let id = &bytes[pos..pos + 4];
+        let size = le32(&bytes[pos + 4..pos + 8]) as usize;
+        let start = pos + 8;
+        let end = start.saturating_add(size).min(bytes.len());
+        match id {
+            b"fmt " => fmt = Some(&bytes[start..end]),
+            b"data" => data = Some(&bytes[start..end]),
+            _ => {}
+        }
+        pos = end + (size & 1); // chunks are word-aligned
+    }
+
+    let fmt = fmt?;
+    let data = data?;
+    if fmt.len() < 16 {
+        return None;
+    }
+    let audio_format = le16(&fmt[0..2]);
+    let channels = le16(&fmt[2..4]).max(1) as usize;
+    let rate = le32(&fmt[4..8]);
+    let bits = le16(&fmt[14..16]);
+
+    // Decode interleaved frames to f32, then downmix to mono by averaging.
+    let (flat, format): (Vec<f32>, SampleFormat) = match (audio_format, bits) {
+        (1, 16) => (
+            data.chunks_exact(2)
+                .map(|c| i16::from_le_bytes([c[0], c[1]]) as f32 / 32768.0)
+                .collect(),
+            SampleFormat::Pcm16,
+        ),
+        (3, 32) => (decode_f32_le(data), SampleFormat::F32),
+        _ => return None,
+    };
 
-    let denoised = tokio::task::spawn_blocking(move || denoise_16k(&samples))
-        .await
-        .unwrap_or_default();
+    let mono = if channels <= 1 {
+        flat
+    } else {
+        flat.chunks(channels)
+            .map(|f| f.iter().sum::<f32>() / f.len() as f32)
+            .collect()
+    };
+    Some((mono, rate, format))
+}
+
+/// Encode mono f32 samples back into a single-channel WAV of the given format.
+fn encode_wav(samples: &[f32], rate: u32, format: SampleFormat) -> Vec<u8> {
+    let (audio_format, bits): (u16, u16) = match format {
+        SampleFormat::F32 => (3, 32),
+        SampleFormat::Pcm16 => (1, 16),
+    };
+    let block_align = bits / 8;
+    let data: Vec<u8> = match format {
+        SampleFormat::F32 => encode_f32_le(samples),
+        SampleFormat::Pcm16 => samples
+            .iter()
+            .flat_map(|s| ((s.clamp(-1.0, 1.0) * 32767.0).round() as i16).to_le_bytes())
+            .collect(),
+    };
 
-    let bytes: Vec<u8> = denoised.iter().flat_map(|s| s.to_le_bytes()).collect();
-    (StatusCode::OK, bytes).into_response()
+    let mut out = Vec::with_capacity(44 + data.len());
+    out.extend_from_slice(b"RIFF");
+    out.extend_from_slice(&(36 + data.len() as u32).to_le_bytes());
+    out.extend_from_slice(b"WAVE");
+    out.extend_from_slice(b"fmt ");
+    out.extend_from_slice(&16u32.to_le_bytes());
+    out.extend_from_slice(&audio_format.to_le_bytes());
+    out.extend_from_slice(&1u16.to_le_bytes()); // channels
+    out.extend_from_slice(&rate.to_le_bytes());
+    out.extend_from_slice(&(rate * block_align as u32).to_le_bytes()); // byte rate
+    out.extend_from_slice(&block_align.to_le_bytes());
+    out.extend_from_slice(&bits.to_le_bytes());
+    out.extend_from_slice(b"data");
+    out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    out.extend_from_slice(&data);
+    out
+}
+
+/// Decode a request body into mono f32 samples using the `Content-Type`, a WAV
+/// sniff, and the optional `rate` query param for raw payloads.
+fn decode_input(content_type: Option<&str>, rate: u32, body: &[u8]) -> Result<AudioInput, &'static str> {
+    let wants_wav = content_type == Some("audio/wav")
+        || (body.len() >= 12 && &body[0..4] == b"RIFF" && &body[8..12] == b"WAVE");
+    if wants_wav {
+        let (samples, rate, format) = parse_wav(body).ok_or("unsupported or malformed WAV body")?;
+        return Ok(AudioInput {
+            samples,
+            rate,
+            container: Container::Wav(format),
+        });
+    }
+
+    if content_type == Some("audio/l16") {
+        if !body.len().is_multiple_of(2) {
+            return Err("audio/l16 body must be 16-bit PCM samples");
+        }
+        // audio/L16 (RFC 2586) is big-endian two's-complement.
+        let samples = body
+            .chunks_exact(2)
+            .map(|c| i16::from_be_bytes([c[0], c[1]]) as f32 / 32768.0)
+            .collect();
+        return Ok(AudioInput {
+            samples,
+            rate,
+            container: Container::RawPcm16,
+        });
+    }
+
+    if !body.len().is_multiple_of(4) {
+        return Err("body must be float32 LE samples");
+    }
+    Ok(AudioInput {
+        samples: decode_f32_le(body),
+        rate,
+        container: Container::RawF32,
+    })
+}
+
+async fn handle_denoise(
+    State(state): State<AppState>,
+    axum::extract::Query(params): axum::extract::Query<DenoiseParams>,
+    headers: axum::http::HeaderMap,
+    body: Bytes,
+) -> impl IntoResponse {
+    let content_type = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.split(';').next().unwrap_or(v).trim());
+
+    let input = match decode_input(content_type, params.rate.unwrap_or(16_000), &body) {
+        Ok(input) => input,
+        Err(msg) => return (StatusCode::BAD_REQUEST, msg).into_response(),
+    };
+
+    // Admit only as many concurrent jobs as the pool can serve; shed load early
+    // rather than letting the blocking queue grow without bound.
+    let permit = match state.sem.clone().try_acquire_owned() {
+        Ok(permit) => permit,
+        Err(_) => {
+            return (
+                StatusCode::SERVICE_UNAVAILABLE,
+                [(header::RETRY_AFTER, "1")],
+                "denoise server at capacity",
+            )
+                .into_response();
+        }
+    };
+
+    let want_vad = params.vad.is_some_and(|v| v != 0);
+    let fast = params.fast.is_some_and(|v| v != 0);
+    let gate = params.gate;
+    let AudioInput {
+        samples,
+        rate,
+        container,
+    } = input;
+
+    // `spawn_blocking` tasks are uncancellable, so `abort()` wouldn't stop the
+    // denoise loop. Instead the permit rides along inside the closure — released
+    // only when the work genuinely ends — and the loop polls `cancel`
+    // cooperatively so a timed-out job stops occupying a blocking thread.
+    let cancel = Arc::new(AtomicBool::new(false));
+    let task_cancel = cancel.clone();
+    let handle = tokio::task::spawn_blocking(move || {
+        let _permit = permit;
+        denoise_at_rate(&samples, rate, gate, fast, &task_cancel)
+    });
+    let (denoised, vad) = match tokio::time::timeout(state.timeout, handle).await {
+        Ok(Ok(result)) => result,
+        Ok(Err(_)) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+        Err(_) => {
+            cancel.store(true, Ordering::Relaxed);
+            return (StatusCode::GATEWAY_TIMEOUT, "denoise timed out").into_response();
+        }
+    };
+
+    // VAD scores are only carried for raw f32 bodies (appended as a trailing
+    // float32-LE array). The WAV and L16 containers have no room for them, so
+    // the `x-vad-frames` header is set only when the body actually contains the
+    // scores — a client must not be told to look for floats that aren't there.
+    let mut vad_in_body = false;
+    let (bytes, content_type) = match container {
+        Container::Wav(format) => (encode_wav(&denoised, rate, format), "audio/wav"),
+        Container::RawPcm16 => {
+            let pcm = denoised
+                .iter()
+                .flat_map(|s| ((s.clamp(-1.0, 1.0) * 32767.0).round() as i16).to_be_bytes())
+                .collect();
+            (pcm, "audio/l16")
+        }
+        Container::RawF32 => {
+            let mut bytes = encode_f32_le(&denoised);
+            if want_vad {
+                // Trailing float32-LE VAD array followed by a u32-LE length
+                // header, so a client can peel the scores off the end and keep
+                // the leading audio.
+                bytes.extend_from_slice(&encode_f32_le(&vad));
+                bytes.extend_from_slice(&(vad.len() as u32).to_le_bytes());
+                vad_in_body = true;
+            }
+            (bytes, "application/octet-stream")
+        }
+    };
+
+    let mut resp = (StatusCode::OK, bytes).into_response();
+    resp.headers_mut().insert(
+        header::CONTENT_TYPE,
+        axum::http::HeaderValue::from_static(content_type),
+    );
+    if vad_in_body {
+        resp.headers_mut()
+            .insert("x-vad-frames", axum::http::HeaderValue::from(vad.len() as u64));
+    }
+    resp
+}
+
+/// Query parameters for the streaming endpoint.
+#[derive(serde::Deserialize)]
+struct StreamParams {
+    /// When non-zero, use the low-latency linear resampler instead of the FIR.
+    fast: Option<u8>,
+}
+
+async fn handle_denoise_stream(
+    axum::extract::Query(params): axum::extract::Query<StreamParams>,
+    ws: WebSocketUpgrade,
+) -> impl IntoResponse {
+    let fast = params.fast.is_some_and(|v| v != 0);
+    ws.on_upgrade(move |socket| denoise_socket(socket, fast))
+}
+
+async fn denoise_socket(mut socket: WebSocket, fast: bool) {
+    let mut denoiser = StreamDenoiser::new(fast);
+
+    while let Some(Ok(msg)) = socket.recv().await {
+        match msg {
+            Message::Binary(data) => {
+                if data.len() % 4 != 0 {
+                    let _ = socket.send(Message::Close(None)).await;
+                    return;
+                }
+                let samples = decode_f32_le(&data);
+                let out = denoiser.push(&samples);
+                if !out.is_empty()
+                    && socket
+                        .send(Message::Binary(encode_f32_le(&out)))
+                        .await
+                        .is_err()
+                {
+                    return;
+                }
+            }
+            Message::Close(_) => break,
+            _ => {}
+        }
+    }
+
+    let tail = denoiser.flush();
+    if !tail.is_empty() {
+        let _ = socket.send(Message::Binary(encode_f32_le(&tail))).await;
+    }
 }
 
 async fn handle_health() -> &'static str {
     "ok"
 }
 
+/// Build a rustls [`ServerConfig`] from a PEM cert chain and private key when
+/// both `NOISEREDUCE_TLS_CERT` and `NOISEREDUCE_TLS_KEY` are set; `None` keeps
+/// the service plaintext.
+fn tls_config_from_env() -> Option<Arc<ServerConfig>> {
+    let cert = std::env::var("NOISEREDUCE_TLS_CERT").ok()?;
+    let key = std::env::var("NOISEREDUCE_TLS_KEY").ok()?;
+    Some(load_rustls_config(&cert, &key))
+}
+
+fn load_rustls_config(cert_path: &str, key_path: &str) -> Arc<ServerConfig> {
+    use std::fs::File;
+    use std::io::BufReader;
+
+    let certs = rustls_pemfile::certs(&mut BufReader::new(
+        File::open(cert_path).expect("open TLS cert"),
+    ))
+    .collect::<Result<Vec<_>, _>>()
+    .expect("parse TLS cert");
+    let key = rustls_pemfile::private_key(&mut BufReader::new(
+        File::open(key_path).expect("open TLS key"),
+    ))
+    .expect("read TLS key")
+    .expect("no private key found in key file");
+
+    let mut config = ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .expect("invalid TLS cert/key");
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    Arc::new(config)
+}
+
+/// Accept loop for the TLS case: terminate rustls on each connection, then hand
+/// the decrypted stream to the same axum service used for plaintext.
+async fn serve_tls(listener: TcpListener, app: Router, config: Arc<ServerConfig>) {
+    let acceptor = TlsAcceptor::from(config);
+    loop {
+        let (stream, _peer) = match listener.accept().await {
+            Ok(conn) => conn,
+            Err(e) => {
+                // Back off briefly so a persistent failure (e.g. EMFILE) can't
+                // spin a hot loop pinning a core.
+                eprintln!("tls accept failed: {e}");
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                continue;
+            }
+        };
+        let acceptor = acceptor.clone();
+        let app = app.clone();
+        tokio::spawn(async move {
+            let Ok(stream) = acceptor.accept(stream).await else {
+                return;
+            };
+            let io = TokioIo::new(stream);
+            let service =
+                hyper::service::service_fn(move |req: Request<Incoming>| app.clone().call(req));
+            let _ = Builder::new(TokioExecutor::new())
+                .serve_connection_with_upgrades(io, service)
+                .await;
+        });
+    }
+}
+
 #[tokio::main]
 async fn main() {
+    let (state, max_body) = AppState::from_env();
+
     let app = Router::new()
         .route("/denoise", post(handle_denoise))
-        .route("/health", get(handle_health));
+        .route("/denoise/stream", get(handle_denoise_stream))
+        .route("/health", get(handle_health))
+        .layer(DefaultBodyLimit::max(max_body))
+        .with_state(state);
 
     let addr = SocketAddr::from(([0, 0, 0, 0], 5200));
-    eprintln!("noisereduce listening on {addr}");
+    let listener = TcpListener::bind(addr).await.expect("bind failed");
+
+    match tls_config_from_env() {
+        Some(config) => {
+            eprintln!("noisereduce listening on {addr} (TLS)");
+            serve_tls(listener, app, config).await;
+        }
+        None => {
+            eprintln!("noisereduce listening on {addr}");
+            axum::serve(listener, app).await.expect("server failed");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rms(x: &[f32]) -> f32 {
+        (x.iter().map(|&v| v * v).sum::<f32>() / x.len().max(1) as f32).sqrt()
+    }
+
+    /// 48k tone of `freq` Hz, `n` samples long.
+    fn tone_48k(freq: f32, n: usize) -> Vec<f32> {
+        use std::f32::consts::PI;
+        (0..n).map(|i| (2.0 * PI * freq * i as f32 / 48_000.0).sin()).collect()
+    }
 
-    let listener = tokio::net::TcpListener::bind(addr).await.expect("bind failed");
-    axum::serve(listener, app).await.expect("server failed");
+    #[test]
+    fn upsample_triples_length() {
+        let mut r = Resampler3x::new();
+        let input: Vec<f32> = (0..200).map(|i| i as f32).collect();
+        assert_eq!(r.upsample(&input).len(), input.len() * 3);
+    }
+
+    #[test]
+    fn downsample_decimates_length() {
+        let mut r = Resampler3x::new();
+        let input = tone_48k(1_000.0, 600);
+        assert_eq!(r.downsample(&input).len(), input.len().div_ceil(3));
+    }
+
+    #[test]
+    fn upsample_history_is_continuous_across_blocks() {
+        let input = tone_48k(1_000.0, 300);
+        let whole = Resampler3x::new().upsample(&input);
+
+        let mut split = Resampler3x::new();
+        let mut streamed = split.upsample(&input[..128]);
+        streamed.extend(split.upsample(&input[128..]));
+
+        assert_eq!(whole.len(), streamed.len());
+        for (a, b) in whole.iter().zip(&streamed) {
+            assert!((a - b).abs() < 1e-6, "block boundary discontinuity: {a} vs {b}");
+        }
+    }
+
+    #[test]
+    fn downsample_attenuates_above_nyquist() {
+        // A tone well above the 8kHz cutoff must be strongly attenuated rather
+        // than aliased back into the speech band.
+        let high_in = tone_48k(14_000.0, 4_800);
+        let low_in = tone_48k(1_000.0, 4_800);
+        let high_out = Resampler3x::new().downsample(&high_in);
+        let low_out = Resampler3x::new().downsample(&low_in);
+
+        // Ignore filter warm-up at the head of each block.
+        let skip = RESAMPLE_TAPS / 3;
+        let high_rms = rms(&high_out[skip..]);
+        let low_rms = rms(&low_out[skip..]);
+        assert!(
+            high_rms < 0.1 * low_rms,
+            "above-Nyquist tone not attenuated: high={high_rms}, low={low_rms}"
+        );
+    }
+
+    #[test]
+    fn resample_linear_length_and_identity() {
+        let input = tone_48k(1_000.0, 480);
+        assert_eq!(resample_linear(&input, 16_000, 16_000), input);
+        assert_eq!(resample_linear(&input, 8_000, 16_000).len(), input.len() * 2);
+        assert_eq!(resample_linear(&input, 48_000, 16_000).len(), input.len() / 3);
+    }
+
+    #[test]
+    fn wav_f32_round_trips() {
+        let samples = tone_48k(440.0, 256);
+        let wav = encode_wav(&samples, 16_000, SampleFormat::F32);
+        let (decoded, rate, format) = parse_wav(&wav).expect("parse f32 wav");
+        assert_eq!(rate, 16_000);
+        assert!(matches!(format, SampleFormat::F32));
+        assert_eq!(decoded.len(), samples.len());
+        for (a, b) in samples.iter().zip(&decoded) {
+            assert!((a - b).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn wav_pcm16_round_trips_within_quantization() {
+        let samples = tone_48k(440.0, 256);
+        let wav = encode_wav(&samples, 16_000, SampleFormat::Pcm16);
+        let (decoded, rate, format) = parse_wav(&wav).expect("parse pcm16 wav");
+        assert_eq!(rate, 16_000);
+        assert!(matches!(format, SampleFormat::Pcm16));
+        assert_eq!(decoded.len(), samples.len());
+        for (a, b) in samples.iter().zip(&decoded) {
+            // One 16-bit quantization step, plus slack for the 32767/32768
+            // encode/decode scale asymmetry.
+            assert!((a - b).abs() < 1e-4);
+        }
+    }
 }